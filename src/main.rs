@@ -1,10 +1,17 @@
 use anyhow::{Context, Result};
+use chrono::Utc;
 use clap::Parser;
 use colored::Colorize;
-use serde::Deserialize;
+use rand::Rng;
+use rayon::prelude::*;
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
 use serde_yaml::Value;
+use std::collections::HashMap;
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 #[derive(Parser, Debug)]
 #[command(name = "pin-and-bump")]
@@ -14,6 +21,40 @@ struct Args {
     #[arg(long)]
     update: bool,
 
+    /// Allow pinning `uses:` references that resolve to a branch tip instead of a tag.
+    /// Branch pins are not reproducible since the branch can move after pinning.
+    #[arg(long)]
+    allow_branches: bool,
+
+    /// Pin purely from pin-and-bump.lock, making no network calls. Fails if a workflow
+    /// references something not present in the lockfile.
+    #[arg(long, visible_alias = "offline")]
+    frozen: bool,
+
+    /// Re-read workflows and confirm every pinned SHA still matches pin-and-bump.lock,
+    /// without writing any changes. Useful in CI to catch hand-edited pins.
+    #[arg(long)]
+    verify: bool,
+
+    /// Don't consult or update the cross-run resolution cache in the user cache directory.
+    #[arg(long)]
+    no_cache: bool,
+
+    /// How long (in seconds) a cached resolution stays fresh before it's revalidated with a
+    /// conditional request.
+    #[arg(long, default_value_t = 86_400)]
+    cache_ttl: u64,
+
+    /// Report deprecated `::set-output`/`::save-state`/`::set-env` workflow commands and actions
+    /// pinned to a retired runtime (node12/node16), without changing anything.
+    #[arg(long)]
+    lint: bool,
+
+    /// Like --lint, and also rewrite `::set-output`/`::save-state`/`::set-env` commands to their
+    /// `$GITHUB_OUTPUT`/`$GITHUB_STATE`/`$GITHUB_ENV` replacements.
+    #[arg(long)]
+    fix: bool,
+
     /// Path to repository (defaults to current directory)
     #[arg(short, long, default_value = ".")]
     path: PathBuf,
@@ -24,6 +65,294 @@ struct ActionReference {
     owner: String,
     repo: String,
     reference: String,
+    kind: GitReference,
+}
+
+/// The kind of `uses:` reference, classified so resolution can be driven per-kind instead of
+/// guessing tag-then-commit and silently treating the result as if it were a release.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum GitReference {
+    /// A release tag, e.g. `v4.2.0`.
+    Tag(String),
+    /// A major-version "floating" tag like `v4`, conventionally re-pointed at each release
+    /// within that major version.
+    MajorFloat(String),
+    /// A branch name, e.g. `main`. Pinning this is not reproducible: the tip keeps moving.
+    Branch(String),
+    /// An already-resolved commit SHA.
+    Rev(String),
+}
+
+impl GitReference {
+    /// Classify a `uses:` reference string from its shape alone. A bare name can't be told
+    /// apart from a branch without asking the remote, so non-SHA, non-major-float references
+    /// are provisionally classified as [`GitReference::Tag`] and corrected by the resolver if
+    /// the tags endpoint says otherwise.
+    fn classify(reference: &str) -> GitReference {
+        if reference.len() == 40 && reference.chars().all(|c| c.is_ascii_hexdigit()) {
+            return GitReference::Rev(reference.to_string());
+        }
+
+        let digits = reference.strip_prefix('v').unwrap_or(reference);
+        if !digits.is_empty() && digits.chars().all(|c| c.is_ascii_digit()) {
+            return GitReference::MajorFloat(reference.to_string());
+        }
+
+        GitReference::Tag(reference.to_string())
+    }
+}
+
+#[derive(Debug)]
+struct ImageReference {
+    /// `None` means the reference had no registry prefix and defaults to Docker Hub.
+    registry: Option<String>,
+    repository: String,
+    tag: String,
+    /// Whether the reference came from a `uses: docker://...` step vs. an `image:` key.
+    is_docker_uses: bool,
+}
+
+#[derive(Debug)]
+enum PinTarget {
+    Action(ActionReference),
+    Image(ImageReference),
+    /// An action already pinned to a SHA. Left alone unless `--update` is given, in which case
+    /// it's checked against the latest release and moved forward if a newer one exists.
+    PinnedAction(PinnedActionReference),
+}
+
+/// What kind of thing a [`LockKey`] identifies, so an action and an image can never collide
+/// even if their identifier strings happened to match.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum LockTargetKind {
+    Action,
+    Image,
+}
+
+/// Identifies a single pinned reference in the lockfile: an action's `owner/repo` or an image's
+/// `registry/repository`, plus the reference as written in the workflow (`v4`, `node:18`, ...).
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+struct LockKey {
+    kind: LockTargetKind,
+    identifier: String,
+    requested: String,
+}
+
+/// One resolved pin, recorded so re-runs are reproducible and auditable without hitting the
+/// network, modeled on package-lock-style dependency resolution.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct LockEntry {
+    #[serde(flatten)]
+    key: LockKey,
+    /// The resolved commit SHA (actions) or image digest (images).
+    resolved: String,
+    /// The `# ...` comment written back alongside the pin (release tag, `branch ... @ date`,
+    /// or image tag).
+    resolved_tag: String,
+    /// RFC 3339 timestamp of when this entry was last resolved.
+    resolved_at: String,
+    /// The `ETag` GitHub returned for the request that resolved this entry, if any, so the next
+    /// run can send it as `If-None-Match` and skip re-resolving unchanged references.
+    #[serde(default)]
+    etag: Option<String>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct LockFile {
+    #[serde(default)]
+    entries: Vec<LockEntry>,
+}
+
+impl LockFile {
+    fn find(&self, key: &LockKey) -> Option<&LockEntry> {
+        self.entries.iter().find(|entry| &entry.key == key)
+    }
+
+    /// Look up an already-pinned entry by what's left once resolution has already happened:
+    /// the target identity and the comment it was pinned with, rather than the original
+    /// requested reference (which pinning discards).
+    fn find_by_identifier_and_tag(
+        &self,
+        kind: LockTargetKind,
+        identifier: &str,
+        resolved_tag: &str,
+    ) -> Option<&LockEntry> {
+        self.entries.iter().find(|entry| {
+            entry.key.kind == kind
+                && entry.key.identifier == identifier
+                && entry.resolved_tag == resolved_tag
+        })
+    }
+
+    fn upsert(&mut self, entry: LockEntry) {
+        match self.entries.iter_mut().find(|e| e.key == entry.key) {
+            Some(existing) => *existing = entry,
+            None => self.entries.push(entry),
+        }
+    }
+}
+
+fn lockfile_path(repo_path: &Path) -> PathBuf {
+    repo_path.join("pin-and-bump.lock")
+}
+
+fn load_lockfile(path: &Path) -> Result<LockFile> {
+    if !path.exists() {
+        return Ok(LockFile::default());
+    }
+    let content =
+        fs::read_to_string(path).context(format!("Failed to read lockfile: {:?}", path))?;
+    serde_json::from_str(&content).context(format!("Failed to parse lockfile: {:?}", path))
+}
+
+fn save_lockfile(path: &Path, lockfile: &LockFile) -> Result<()> {
+    let mut content = serde_json::to_string_pretty(lockfile).context("Failed to serialize lockfile")?;
+    content.push('\n');
+    fs::write(path, content).context(format!("Failed to write lockfile: {:?}", path))
+}
+
+/// A resolution previously recorded in the cross-run cache.
+struct CachedResolution {
+    resolved: String,
+    resolved_tag: String,
+    etag: Option<String>,
+    fetched_at: i64,
+}
+
+impl CachedResolution {
+    /// Whether this entry is still within `ttl` of when it was fetched, and so can be reused
+    /// without even a conditional request.
+    fn is_fresh(&self, ttl: Duration) -> bool {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs() as i64)
+            .unwrap_or(self.fetched_at);
+        now.saturating_sub(self.fetched_at) <= ttl.as_secs() as i64
+    }
+
+    /// A [`LockEntry`] carrying just enough of this cache row for the conditional-`GET` path in
+    /// [`resolve_reference_with_client`], which reads `resolved`, `resolved_tag` and `etag`.
+    fn as_lock_entry(&self, key: &LockKey) -> LockEntry {
+        LockEntry {
+            key: key.clone(),
+            resolved: self.resolved.clone(),
+            resolved_tag: self.resolved_tag.clone(),
+            resolved_at: String::new(),
+            etag: self.etag.clone(),
+        }
+    }
+}
+
+/// A persistent, cross-repo, cross-run cache of resolved references, so pinning the same popular
+/// action or image across many workflows or many repos doesn't re-hit the network every time.
+/// Wrapped in a `Mutex` because `rusqlite::Connection` isn't `Sync` and resolution runs
+/// concurrently across a rayon pool.
+struct RefCache {
+    conn: Mutex<Connection>,
+}
+
+impl RefCache {
+    /// Open (creating if necessary) the cache database at `path`, running schema setup in a
+    /// single transaction.
+    fn open(path: &Path) -> Result<Self> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)
+                .context(format!("Failed to create cache directory: {:?}", parent))?;
+        }
+
+        let mut conn =
+            Connection::open(path).context(format!("Failed to open ref cache: {:?}", path))?;
+
+        let tx = conn.transaction().context("Failed to start cache setup transaction")?;
+        tx.execute(
+            "CREATE TABLE IF NOT EXISTS resolved_refs (
+                kind TEXT NOT NULL,
+                identifier TEXT NOT NULL,
+                requested TEXT NOT NULL,
+                resolved TEXT NOT NULL,
+                resolved_tag TEXT NOT NULL,
+                etag TEXT,
+                fetched_at INTEGER NOT NULL,
+                PRIMARY KEY (kind, identifier, requested)
+            )",
+            [],
+        )
+        .context("Failed to create resolved_refs table")?;
+        tx.commit().context("Failed to commit cache setup transaction")?;
+
+        Ok(Self { conn: Mutex::new(conn) })
+    }
+
+    /// Open the cache at the platform's user cache directory, or return `None` (rather than
+    /// failing the whole run) if the cache directory can't be determined or opened — the cache
+    /// is a performance optimization, not a correctness requirement.
+    fn open_default() -> Option<Self> {
+        let cache_dir = dirs::cache_dir()?.join("pin-and-bump");
+        match Self::open(&cache_dir.join("resolved-refs.sqlite3")) {
+            Ok(cache) => Some(cache),
+            Err(e) => {
+                eprintln!("  Warning: could not open ref cache, continuing without it: {}", e);
+                None
+            }
+        }
+    }
+
+    fn get(&self, key: &LockKey) -> Result<Option<CachedResolution>> {
+        let conn = self.conn.lock().expect("ref cache mutex poisoned");
+        conn.query_row(
+            "SELECT resolved, resolved_tag, etag, fetched_at FROM resolved_refs
+             WHERE kind = ?1 AND identifier = ?2 AND requested = ?3",
+            params![lock_target_kind_str(key.kind), key.identifier, key.requested],
+            |row| {
+                Ok(CachedResolution {
+                    resolved: row.get(0)?,
+                    resolved_tag: row.get(1)?,
+                    etag: row.get(2)?,
+                    fetched_at: row.get(3)?,
+                })
+            },
+        )
+        .optional()
+        .context("Failed to query ref cache")
+    }
+
+    fn put(&self, key: &LockKey, resolved: &str, resolved_tag: &str, etag: Option<&str>) -> Result<()> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs() as i64)
+            .unwrap_or(0);
+
+        let conn = self.conn.lock().expect("ref cache mutex poisoned");
+        conn.execute(
+            "INSERT INTO resolved_refs (kind, identifier, requested, resolved, resolved_tag, etag, fetched_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
+             ON CONFLICT (kind, identifier, requested) DO UPDATE SET
+                resolved = excluded.resolved,
+                resolved_tag = excluded.resolved_tag,
+                etag = excluded.etag,
+                fetched_at = excluded.fetched_at",
+            params![
+                lock_target_kind_str(key.kind),
+                key.identifier,
+                key.requested,
+                resolved,
+                resolved_tag,
+                etag,
+                now
+            ],
+        )
+        .context("Failed to write to ref cache")?;
+
+        Ok(())
+    }
+}
+
+fn lock_target_kind_str(kind: LockTargetKind) -> &'static str {
+    match kind {
+        LockTargetKind::Action => "action",
+        LockTargetKind::Image => "image",
+    }
 }
 
 #[derive(Debug, Deserialize)]
@@ -66,60 +395,442 @@ fn main() -> Result<()> {
         return Ok(());
     }
 
-    // Process each workflow file
-    for workflow_file in workflow_files {
-        process_workflow_file(&workflow_file, args.update)?;
+    let lock_path = lockfile_path(&args.path);
+    let mut lockfile = load_lockfile(&lock_path)?;
+
+    if args.verify {
+        let mut all_ok = true;
+        for workflow_file in &workflow_files {
+            if !verify_workflow_file(workflow_file, &lockfile)? {
+                all_ok = false;
+            }
+        }
+        if !all_ok {
+            anyhow::bail!("one or more pinned references do not match pin-and-bump.lock");
+        }
+        println!("All pinned references match pin-and-bump.lock");
+        return Ok(());
+    }
+
+    if args.lint || args.fix {
+        let client = build_github_client()?;
+        let mut any_findings = false;
+        for workflow_file in &workflow_files {
+            if lint_workflow_file(workflow_file, &client, args.fix)? {
+                any_findings = true;
+            }
+        }
+        if any_findings {
+            anyhow::bail!("lint found deprecated Actions usage");
+        }
+        println!("No deprecated Actions usage found");
+        return Ok(());
+    }
+
+    // Parse every workflow file up front so resolution can be deduplicated and run concurrently
+    // instead of resolving each `uses:`/`image:` reference one at a time.
+    let mut parsed_files = Vec::new();
+    for workflow_file in &workflow_files {
+        let content = fs::read_to_string(workflow_file)
+            .context(format!("Failed to read file: {:?}", workflow_file))?;
+        let targets = find_action_references(&content)?;
+        parsed_files.push((workflow_file.clone(), content, targets));
+    }
+
+    let all_targets: Vec<&PinTarget> = parsed_files
+        .iter()
+        .flat_map(|(_, _, targets)| targets.iter())
+        .collect();
+
+    let client = build_github_client()?;
+    let registry_client = build_registry_client()?;
+    let ref_cache = if args.frozen || args.no_cache {
+        None
+    } else {
+        RefCache::open_default()
+    };
+    let resolved = resolve_pin_targets_concurrently(
+        &all_targets,
+        args.update,
+        args.allow_branches,
+        args.frozen,
+        &lockfile,
+        &client,
+        &registry_client,
+        ref_cache.as_ref(),
+        Duration::from_secs(args.cache_ttl),
+    );
+
+    let mut all_resolved = true;
+    for (path, content, targets) in &parsed_files {
+        if !apply_resolved_pins(path, content, targets, &resolved, &mut lockfile, args.frozen)? {
+            all_resolved = false;
+        }
+    }
+
+    if args.frozen {
+        if !all_resolved {
+            anyhow::bail!(
+                "one or more references could not be resolved from pin-and-bump.lock (run without --frozen to populate it)"
+            );
+        }
+    } else {
+        save_lockfile(&lock_path, &lockfile)?;
     }
 
     Ok(())
 }
 
-fn process_workflow_file(file_path: &PathBuf, update: bool) -> Result<()> {
-    let content =
-        fs::read_to_string(file_path).context(format!("Failed to read file: {:?}", file_path))?;
+/// Build the shared GitHub API client used for all resolution. Authenticates with
+/// `GITHUB_TOKEN`/`GH_TOKEN` when set, raising the rate limit from 60/hr to 5000/hr.
+fn build_github_client() -> Result<reqwest::blocking::Client> {
+    let mut builder = reqwest::blocking::Client::builder().user_agent("pin-and-bump/0.1.0");
+
+    if let Ok(token) = std::env::var("GITHUB_TOKEN").or_else(|_| std::env::var("GH_TOKEN")) {
+        let mut headers = reqwest::header::HeaderMap::new();
+        let mut auth_value = reqwest::header::HeaderValue::from_str(&format!("Bearer {}", token))
+            .context("GITHUB_TOKEN/GH_TOKEN was not a valid header value")?;
+        auth_value.set_sensitive(true);
+        headers.insert(reqwest::header::AUTHORIZATION, auth_value);
+        builder = builder.default_headers(headers);
+    }
 
-    let action_refs = find_action_references(&content)?;
+    builder.build().context("Failed to build GitHub HTTP client")
+}
 
-    if action_refs.is_empty() {
-        return Ok(());
+/// Build the client used for OCI registry lookups (`resolve_image_digest`). This must carry no
+/// default `Authorization` header: unlike `build_github_client`, it's used against arbitrary
+/// third-party registry hosts (Docker Hub, ghcr.io, quay.io, ...), and sending a
+/// `GITHUB_TOKEN`/`GH_TOKEN` Bearer token to one of those would leak a GitHub credential to a
+/// host that has nothing to do with GitHub. Registry auth is instead layered in per-request once
+/// the 401 challenge handshake resolves a registry-scoped token (see `authenticate_with_registry`).
+fn build_registry_client() -> Result<reqwest::blocking::Client> {
+    reqwest::blocking::Client::builder()
+        .user_agent("pin-and-bump/0.1.0")
+        .build()
+        .context("Failed to build registry HTTP client")
+}
+
+fn lock_key_for_target(target: &PinTarget) -> LockKey {
+    match target {
+        PinTarget::Action(action_ref) => LockKey {
+            kind: LockTargetKind::Action,
+            identifier: format!("{}/{}", action_ref.owner, action_ref.repo),
+            requested: action_ref.reference.clone(),
+        },
+        PinTarget::Image(image_ref) => {
+            let (registry_host, repository) = registry_host_and_repo(image_ref);
+            LockKey {
+                kind: LockTargetKind::Image,
+                identifier: format!("{}/{}", registry_host, repository),
+                requested: image_ref.tag.clone(),
+            }
+        }
+        PinTarget::PinnedAction(pinned) => LockKey {
+            kind: LockTargetKind::Action,
+            identifier: pinned.identifier.clone(),
+            requested: pinned.sha.clone(),
+        },
+    }
+}
+
+/// A target's resolved `(sha_or_digest, version_comment, etag)`, or the stringified error from
+/// trying to resolve it. Errors are stringified rather than carried as `anyhow::Error` so the map
+/// stays `Send`-able out of the rayon thread pool.
+type ResolutionResult = std::result::Result<(String, String, Option<String>), String>;
+
+/// Resolve every distinct `(owner/repo, reference)` or `(registry/repository, tag)` across all
+/// workflow files exactly once, concurrently.
+#[allow(clippy::too_many_arguments)]
+fn resolve_pin_targets_concurrently(
+    targets: &[&PinTarget],
+    update: bool,
+    allow_branches: bool,
+    frozen: bool,
+    lockfile: &LockFile,
+    github_client: &reqwest::blocking::Client,
+    registry_client: &reqwest::blocking::Client,
+    ref_cache: Option<&RefCache>,
+    cache_ttl: Duration,
+) -> HashMap<LockKey, ResolutionResult> {
+    let mut unique: HashMap<LockKey, &PinTarget> = HashMap::new();
+    for target in targets {
+        unique.entry(lock_key_for_target(target)).or_insert(*target);
+    }
+
+    unique
+        .into_par_iter()
+        .map(|(key, target)| {
+            let result = resolve_pin_target(
+                target,
+                &key,
+                update,
+                allow_branches,
+                frozen,
+                lockfile,
+                github_client,
+                registry_client,
+                ref_cache,
+                cache_ttl,
+            )
+            .map_err(|e| e.to_string());
+            (key, result)
+        })
+        .collect()
+}
+
+#[allow(clippy::too_many_arguments)]
+fn resolve_pin_target(
+    target: &PinTarget,
+    key: &LockKey,
+    update: bool,
+    allow_branches: bool,
+    frozen: bool,
+    lockfile: &LockFile,
+    github_client: &reqwest::blocking::Client,
+    registry_client: &reqwest::blocking::Client,
+    ref_cache: Option<&RefCache>,
+    cache_ttl: Duration,
+) -> Result<(String, String, Option<String>)> {
+    let lock_cached = lockfile.find(key);
+
+    if frozen {
+        return lock_cached
+            .map(|entry| (entry.resolved.clone(), entry.resolved_tag.clone(), entry.etag.clone()))
+            .context(format!(
+                "`{}@{}` is not present in pin-and-bump.lock; run without --frozen to resolve it",
+                key.identifier, key.requested
+            ));
+    }
+
+    let cache_row = ref_cache.and_then(|cache| cache.get(key).ok().flatten());
+    if let Some(row) = &cache_row {
+        if row.is_fresh(cache_ttl) {
+            return Ok((row.resolved.clone(), row.resolved_tag.clone(), row.etag.clone()));
+        }
+    }
+
+    // Revalidate a stale cache-db entry with a conditional request using its ETag. Fall back to
+    // the lockfile's own ETag when there's no cache-db entry at all, so `--no-cache` runs can
+    // still send a conditional request instead of always re-resolving.
+    let conditional_source = cache_row
+        .as_ref()
+        .map(|row| row.as_lock_entry(key))
+        .or_else(|| lock_cached.cloned());
+
+    let (resolved, resolved_tag, etag) = match target {
+        PinTarget::Action(action_ref) => {
+            let (sha, kind, etag) = resolve_reference_with_client(
+                action_ref,
+                update,
+                allow_branches,
+                github_client,
+                "https://api.github.com",
+                conditional_source.as_ref(),
+            )?;
+            (sha, version_comment(&kind), etag)
+        }
+        PinTarget::Image(image_ref) => {
+            let (registry_host, repository) = registry_host_and_repo(image_ref);
+            let digest =
+                resolve_image_digest(registry_client, &registry_host, &repository, &image_ref.tag)?;
+            (digest, image_ref.tag.clone(), None)
+        }
+        PinTarget::PinnedAction(pinned) => {
+            if !update {
+                (pinned.sha.clone(), pinned.tag_comment.clone(), None)
+            } else {
+                match resolve_latest_for_pinned_action(
+                    github_client,
+                    "https://api.github.com",
+                    &pinned.identifier,
+                )? {
+                    Some((sha, tag)) => (sha, tag, None),
+                    None => (pinned.sha.clone(), pinned.tag_comment.clone(), None),
+                }
+            }
+        }
+    };
+
+    if let Some(cache) = ref_cache {
+        if let Err(e) = cache.put(key, &resolved, &resolved_tag, etag.as_deref()) {
+            eprintln!("  Warning: could not write to ref cache: {}", e);
+        }
+    }
+
+    Ok((resolved, resolved_tag, etag))
+}
+
+/// Find the next line at or after `*cursor` whose `key:` value contains `old_value`, and replace
+/// just that occurrence with `new_value`, dropping anything after it on the line (a stale trailing
+/// comment included) since `new_value` carries its own refreshed comment. Advances `*cursor` past
+/// the matched line so a later target with an identical value (e.g. the same action used in two
+/// jobs) can't be matched twice. Returns `false` if no matching line was found from the cursor on.
+///
+/// This relies on `find_action_references`'s tree walk visiting `uses:`/`image:` nodes in the same
+/// top-to-bottom order they appear in block-style YAML, so each target lines up with the next
+/// unclaimed occurrence of its value rather than the first occurrence anywhere in the file.
+fn rewrite_line_value(lines: &mut [String], cursor: &mut usize, key: &str, old_value: &str, new_value: &str) -> bool {
+    for (i, line) in lines.iter_mut().enumerate().skip(*cursor) {
+        let Some(key_pos) = line.find(key) else { continue };
+        let Some(value_pos) = line[key_pos..].find(old_value).map(|p| p + key_pos) else { continue };
+        if value_pos <= key_pos + key.len() {
+            continue;
+        }
+
+        *line = format!("{}{}", &line[..value_pos], new_value);
+        *cursor = i + 1;
+        return true;
+    }
+    false
+}
+
+/// Rewrite `file_path` using the already-resolved values in `resolved`, and record each
+/// successful resolution in the lockfile. Resolution itself has already happened (concurrently,
+/// once per distinct reference) by the time this runs, so this step is pure editing of the lines
+/// that changed — everything else in the file, including formatting and unrelated comments, is
+/// left byte-for-byte alone.
+///
+/// Returns `false` (after printing a diagnostic per failure, same as before) if any target's
+/// resolution was an `Err`, so `main()` can fail loudly under `--frozen` instead of silently
+/// leaving a reference unpinned.
+fn apply_resolved_pins(
+    file_path: &PathBuf,
+    content: &str,
+    pin_targets: &[PinTarget],
+    resolved: &HashMap<LockKey, ResolutionResult>,
+    lockfile: &mut LockFile,
+    frozen: bool,
+) -> Result<bool> {
+    if pin_targets.is_empty() {
+        return Ok(true);
     }
 
     println!("\nProcessing: {}", file_path.display());
 
-    let mut updated_content = content.clone();
+    let mut lines: Vec<String> = content.lines().map(|l| l.to_string()).collect();
+    let mut cursor = 0usize;
     let mut changes = Vec::new();
+    let mut all_ok = true;
+
+    for target in pin_targets {
+        let lock_key = lock_key_for_target(target);
+        let result = resolved
+            .get(&lock_key)
+            .expect("every pin target was resolved before files were rewritten");
+
+        match target {
+            PinTarget::Action(action_ref) => match result {
+                Ok((sha, comment, etag)) => {
+                    let old_uses = format!(
+                        "{}/{}@{}",
+                        action_ref.owner, action_ref.repo, action_ref.reference
+                    );
+                    let new_uses =
+                        format!("{}/{}@{} # {}", action_ref.owner, action_ref.repo, sha, comment);
+
+                    // Only update if it's not already pinned to this SHA
+                    if !action_ref.reference.starts_with(&sha[..7])
+                        && rewrite_line_value(&mut lines, &mut cursor, "uses:", &old_uses, &new_uses)
+                    {
+                        changes.push((old_uses, new_uses));
+                    }
 
-    for action_ref in action_refs {
-        match resolve_reference(&action_ref, update) {
-            Ok((sha, version_tag)) => {
-                let old_uses = format!(
-                    "{}/{}@{}",
-                    action_ref.owner, action_ref.repo, action_ref.reference
-                );
-                let new_uses = format!(
-                    "{}/{}@{} # {}",
-                    action_ref.owner, action_ref.repo, sha, version_tag
-                );
+                    if !frozen {
+                        lockfile.upsert(LockEntry {
+                            key: lock_key,
+                            resolved: sha.clone(),
+                            resolved_tag: comment.clone(),
+                            resolved_at: Utc::now().to_rfc3339(),
+                            etag: etag.clone(),
+                        });
+                    }
+                }
+                Err(e) => {
+                    all_ok = false;
+                    eprintln!(
+                        "  Error resolving {}/{}@{}: {}",
+                        action_ref.owner, action_ref.repo, action_ref.reference, e
+                    );
+                }
+            },
+            PinTarget::Image(image_ref) => match result {
+                Ok((digest, tag, etag)) => {
+                    let key = if image_ref.is_docker_uses { "uses:" } else { "image:" };
+                    let prefix = if image_ref.is_docker_uses { "docker://" } else { "" };
+                    let registry_prefix = image_ref
+                        .registry
+                        .as_ref()
+                        .map(|registry| format!("{}/", registry))
+                        .unwrap_or_default();
+
+                    let old_value = format!(
+                        "{}{}{}:{}",
+                        prefix, registry_prefix, image_ref.repository, image_ref.tag
+                    );
+                    let new_value = format!(
+                        "{}{}{}@{} # {}",
+                        prefix, registry_prefix, image_ref.repository, digest, tag
+                    );
 
-                // Only update if it's not already pinned to this SHA
-                if !action_ref.reference.starts_with(&sha[..7]) {
-                    updated_content = updated_content.replace(
-                        &format!("uses: {}", old_uses),
-                        &format!("uses: {}", new_uses),
+                    if rewrite_line_value(&mut lines, &mut cursor, key, &old_value, &new_value) {
+                        changes.push((old_value, new_value));
+                    }
+
+                    if !frozen {
+                        lockfile.upsert(LockEntry {
+                            key: lock_key,
+                            resolved: digest.clone(),
+                            resolved_tag: tag.clone(),
+                            resolved_at: Utc::now().to_rfc3339(),
+                            etag: etag.clone(),
+                        });
+                    }
+                }
+                Err(e) => {
+                    all_ok = false;
+                    eprintln!(
+                        "  Error resolving image {}:{}: {}",
+                        image_ref.repository, image_ref.tag, e
                     );
-                    changes.push((old_uses, new_uses));
                 }
-            }
-            Err(e) => {
-                eprintln!(
-                    "  Error resolving {}/{}@{}: {}",
-                    action_ref.owner, action_ref.repo, action_ref.reference, e
-                );
-            }
+            },
+            PinTarget::PinnedAction(pinned) => match result {
+                Ok((sha, comment, etag)) => {
+                    if *sha != pinned.sha {
+                        let old_uses = format!("{}@{}", pinned.identifier, pinned.sha);
+                        let new_uses = format!("{}@{} # {}", pinned.identifier, sha, comment);
+
+                        if rewrite_line_value(&mut lines, &mut cursor, "uses:", &old_uses, &new_uses) {
+                            changes.push((old_uses, new_uses));
+                        }
+                    }
+
+                    if !frozen {
+                        lockfile.upsert(LockEntry {
+                            key: lock_key,
+                            resolved: sha.clone(),
+                            resolved_tag: comment.clone(),
+                            resolved_at: Utc::now().to_rfc3339(),
+                            etag: etag.clone(),
+                        });
+                    }
+                }
+                Err(e) => {
+                    all_ok = false;
+                    eprintln!("  Error checking latest release for {}: {}", pinned.identifier, e);
+                }
+            },
         }
     }
 
     if !changes.is_empty() {
+        let line_ending = if content.contains("\r\n") { "\r\n" } else { "\n" };
+        let mut updated_content = lines.join(line_ending);
+        if content.ends_with('\n') {
+            updated_content.push_str(line_ending);
+        }
+
         fs::write(file_path, updated_content)
             .context(format!("Failed to write file: {:?}", file_path))?;
 
@@ -128,42 +839,457 @@ fn process_workflow_file(file_path: &PathBuf, update: bool) -> Result<()> {
         }
     }
 
-    Ok(())
+    Ok(all_ok)
+}
+
+/// An already-pinned `uses: owner/repo@<sha> # <tag>` reference, found either while verifying or
+/// while checking whether an `--update` run should move the pin forward to a newer release.
+#[derive(Debug, Clone)]
+struct PinnedActionReference {
+    identifier: String,
+    sha: String,
+    tag_comment: String,
+}
+
+/// Re-scan a workflow for already-pinned action references (the ones `parse_uses_string` skips)
+/// so `--verify` can confirm they still match what's recorded in the lockfile.
+///
+/// This scans raw lines rather than the parsed YAML tree: `serde_yaml` strips a scalar's trailing
+/// `# ...` comment before the value ever reaches our code, so the version comment that identifies
+/// *which* release a SHA is supposed to be can only be recovered from the source text itself.
+fn find_pinned_references(content: &str) -> Result<Vec<PinnedActionReference>> {
+    let mut pinned = Vec::new();
+    for line in content.lines() {
+        if let Some((_, rest)) = line.split_once("uses:") {
+            if let Some(p) = parse_pinned_uses_string(rest.trim()) {
+                pinned.push(p);
+            }
+        }
+    }
+    Ok(pinned)
+}
+
+/// Parse a raw `owner/repo@<sha> # <tag>` line fragment (the part of a `uses:` line after the
+/// key) into a [`PinnedActionReference`]. Returns `None` if it isn't SHA-pinned or has no trailing
+/// comment to recover a tag from.
+fn parse_pinned_uses_string(uses: &str) -> Option<PinnedActionReference> {
+    let mut parts = uses.splitn(2, '#');
+    let head = parts.next()?.trim();
+    let tag_comment = parts.next()?.trim().to_string();
+
+    let (identifier, sha) = head.rsplit_once('@')?;
+    if !(sha.len() == 40 && sha.chars().all(|c| c.is_ascii_hexdigit())) {
+        return None;
+    }
+
+    Some(PinnedActionReference {
+        identifier: identifier.to_string(),
+        sha: sha.to_string(),
+        tag_comment,
+    })
+}
+
+/// Find the `identifier@sha` pin (already known not to have a comment in the parsed YAML value)
+/// on its source line, starting the search at `*cursor`, and recover its trailing `# <tag>`
+/// comment from the raw text. Advances `*cursor` past the matched line, same as
+/// [`rewrite_line_value`], so two identical pins in different jobs are each matched to their own
+/// line. Returns `None` if the line has no comment to recover a tag from.
+fn find_pinned_action_on_line(
+    lines: &[&str],
+    cursor: &mut usize,
+    identifier: &str,
+    sha: &str,
+) -> Option<PinnedActionReference> {
+    let needle = format!("{}@{}", identifier, sha);
+    for (i, line) in lines.iter().enumerate().skip(*cursor) {
+        let Some(key_pos) = line.find("uses:") else { continue };
+        let Some(needle_pos) = line[key_pos..].find(&needle).map(|p| p + key_pos) else { continue };
+
+        *cursor = i + 1;
+        let after = &line[needle_pos + needle.len()..];
+        let tag_comment = after.split_once('#')?.1.trim().to_string();
+        return Some(PinnedActionReference {
+            identifier: identifier.to_string(),
+            sha: sha.to_string(),
+            tag_comment,
+        });
+    }
+    None
+}
+
+/// An already-pinned `image: name@sha256:... # tag` or `uses: docker://name@sha256:... # tag`
+/// reference, found while verifying that container/image pins still match the lockfile. `identifier`
+/// is the registry host and repository, normalized the same way [`lock_key_for_target`] normalizes
+/// an [`ImageReference`] (Docker Hub default host, `library/` namespace), so it can be looked up
+/// against [`LockTargetKind::Image`] entries directly.
+#[derive(Debug, Clone)]
+struct PinnedImageReference {
+    identifier: String,
+    digest: String,
+    tag_comment: String,
+}
+
+/// Re-scan a workflow for already-pinned image/container references (the ones
+/// `parse_image_string`/`parse_docker_uses_string` skip because they're already digest-pinned) so
+/// `--verify` can confirm they still match what's recorded in the lockfile, mirroring
+/// `find_pinned_references` for actions.
+fn find_pinned_image_references(content: &str) -> Result<Vec<PinnedImageReference>> {
+    let mut pinned = Vec::new();
+    for line in content.lines() {
+        if let Some((_, rest)) = line.split_once("uses:") {
+            if let Some(docker_image) = rest.trim().strip_prefix("docker://") {
+                if let Some(p) = parse_pinned_image_string(docker_image, true) {
+                    pinned.push(p);
+                }
+                continue;
+            }
+        }
+        if let Some((_, rest)) = line.split_once("image:") {
+            if let Some(p) = parse_pinned_image_string(rest.trim(), false) {
+                pinned.push(p);
+            }
+        }
+    }
+    Ok(pinned)
+}
+
+/// Parse a raw `[registry/]repository@sha256:<hex> # <tag>` line fragment (the part of an
+/// `image:`/`uses: docker://` line after the key) into a [`PinnedImageReference`]. Returns `None`
+/// if it isn't digest-pinned or has no trailing comment to recover a tag from.
+fn parse_pinned_image_string(value: &str, is_docker_uses: bool) -> Option<PinnedImageReference> {
+    let mut parts = value.splitn(2, '#');
+    let head = parts.next()?.trim();
+    let tag_comment = parts.next()?.trim().to_string();
+
+    let (repo_and_registry, digest) = head.rsplit_once('@')?;
+    if !digest.starts_with("sha256:") {
+        return None;
+    }
+
+    let (registry, repository) = split_registry_and_repo(repo_and_registry);
+    let image_ref = ImageReference {
+        registry,
+        repository,
+        tag: String::new(),
+        is_docker_uses,
+    };
+    let (registry_host, repository) = registry_host_and_repo(&image_ref);
+
+    Some(PinnedImageReference {
+        identifier: format!("{}/{}", registry_host, repository),
+        digest: digest.to_string(),
+        tag_comment,
+    })
+}
+
+/// Confirm every already-pinned action and image/container reference in `file_path` still matches
+/// what's recorded in the lockfile, without writing anything. Returns `false` (after printing
+/// diagnostics) if any pin is missing from the lock or no longer matches it.
+fn verify_workflow_file(file_path: &PathBuf, lockfile: &LockFile) -> Result<bool> {
+    let content =
+        fs::read_to_string(file_path).context(format!("Failed to read file: {:?}", file_path))?;
+
+    let pinned = find_pinned_references(&content)?;
+    let pinned_images = find_pinned_image_references(&content)?;
+    let mut all_ok = true;
+
+    for p in pinned {
+        match lockfile.find_by_identifier_and_tag(LockTargetKind::Action, &p.identifier, &p.tag_comment) {
+            Some(entry) if entry.resolved == p.sha => {}
+            Some(entry) => {
+                all_ok = false;
+                eprintln!(
+                    "  {} {}: {}@{} is pinned to {} but the lock expects {}",
+                    "MISMATCH".red(),
+                    file_path.display(),
+                    p.identifier,
+                    p.tag_comment,
+                    p.sha,
+                    entry.resolved
+                );
+            }
+            None => {
+                all_ok = false;
+                eprintln!(
+                    "  {} {}: {}@{} is not recorded in pin-and-bump.lock",
+                    "UNKNOWN".red(),
+                    file_path.display(),
+                    p.identifier,
+                    p.tag_comment
+                );
+            }
+        }
+    }
+
+    for p in pinned_images {
+        match lockfile.find_by_identifier_and_tag(LockTargetKind::Image, &p.identifier, &p.tag_comment) {
+            Some(entry) if entry.resolved == p.digest => {}
+            Some(entry) => {
+                all_ok = false;
+                eprintln!(
+                    "  {} {}: {}:{} is pinned to {} but the lock expects {}",
+                    "MISMATCH".red(),
+                    file_path.display(),
+                    p.identifier,
+                    p.tag_comment,
+                    p.digest,
+                    entry.resolved
+                );
+            }
+            None => {
+                all_ok = false;
+                eprintln!(
+                    "  {} {}: {}:{} is not recorded in pin-and-bump.lock",
+                    "UNKNOWN".red(),
+                    file_path.display(),
+                    p.identifier,
+                    p.tag_comment
+                );
+            }
+        }
+    }
+
+    Ok(all_ok)
+}
+
+/// A deprecated workflow command, and the env-file it should be rewritten to append to instead.
+const DEPRECATED_COMMANDS: [(&str, &str); 3] = [
+    ("set-output", "GITHUB_OUTPUT"),
+    ("save-state", "GITHUB_STATE"),
+    ("set-env", "GITHUB_ENV"),
+];
+
+/// Runtimes GitHub has retired; actions still declaring one of these in `runs.using` need
+/// bumping to a release built on a supported runtime.
+const RETIRED_RUNTIMES: [&str; 2] = ["node12", "node16"];
+
+/// Scan every line of a workflow for a deprecated `::set-output`/`::save-state`/`::set-env`
+/// workflow command, returning its 1-indexed line number and the line's trimmed text. These
+/// commands only make sense inside `run:` steps, so a plain line scan (rather than tracking
+/// which YAML node each line belongs to) is enough to find them.
+fn find_deprecated_commands(content: &str) -> Vec<(usize, String)> {
+    content
+        .lines()
+        .enumerate()
+        .filter_map(|(i, line)| {
+            let is_deprecated = DEPRECATED_COMMANDS
+                .iter()
+                .any(|(command, _)| line.contains(&format!("::{}", command)));
+            if is_deprecated {
+                Some((i + 1, line.trim().to_string()))
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+/// Rewrite a `run:` line using the `echo "::<command> name=<name>::<value>"` form to the
+/// `echo "<name>=<value>" >> "$<ENV_FILE>"` form GitHub now recommends. Returns `None` if the
+/// line doesn't match that exact shape (e.g. the value is built up with shell variables instead
+/// of a literal echo), leaving it for a human to migrate by hand.
+fn rewrite_deprecated_command_line(line: &str) -> Option<String> {
+    let marker_pos = line.find("echo \"::")?;
+    let prefix = &line[..marker_pos];
+    let after_echo = &line[marker_pos + "echo \"".len()..];
+
+    for (command, env_file) in DEPRECATED_COMMANDS {
+        let marker = format!("::{} name=", command);
+        let Some(rest) = after_echo.strip_prefix(&marker) else {
+            continue;
+        };
+        let Some((name, remainder)) = rest.split_once("::") else {
+            continue;
+        };
+        let value = remainder.strip_suffix('"').unwrap_or(remainder);
+        return Some(format!(r#"{prefix}echo "{name}={value}" >> "${env_file}""#));
+    }
+
+    None
+}
+
+/// Rewrite every deprecated workflow command line in `content`, returning the new content and
+/// how many lines were changed.
+fn fix_deprecated_commands(content: &str) -> (String, usize) {
+    let mut changed = 0;
+    let had_trailing_newline = content.ends_with('\n');
+
+    let lines: Vec<String> = content
+        .lines()
+        .map(|line| match rewrite_deprecated_command_line(line) {
+            Some(new_line) => {
+                changed += 1;
+                new_line
+            }
+            None => line.to_string(),
+        })
+        .collect();
+
+    let mut result = lines.join("\n");
+    if had_trailing_newline {
+        result.push('\n');
+    }
+    (result, changed)
+}
+
+/// Look up `key` in `value` if it's a YAML mapping, mirroring how `extract_uses_from_value`
+/// navigates the tree elsewhere in this file.
+fn mapping_get<'a>(value: &'a Value, key: &str) -> Option<&'a Value> {
+    match value {
+        Value::Mapping(map) => map.get(Value::String(key.to_string())),
+        _ => None,
+    }
+}
+
+/// Fetch `owner/repo`'s `action.yml` at `sha` and check whether it declares a retired runtime.
+/// Returns `Ok(None)` for composite/Docker actions without a `runs.using`, or when the file can't
+/// be fetched at all (e.g. a private repo) — an inconclusive check shouldn't fail the whole lint.
+fn check_runtime_deprecation(
+    client: &reqwest::blocking::Client,
+    identifier: &str,
+    sha: &str,
+) -> Result<Option<String>> {
+    for filename in ["action.yml", "action.yaml"] {
+        let url = format!("https://raw.githubusercontent.com/{}/{}/{}", identifier, sha, filename);
+        let response = send_with_rate_limit_retry(client.get(&url))?;
+        if !response.status().is_success() {
+            continue;
+        }
+
+        let text = response.text()?;
+        let yaml: Value = serde_yaml::from_str(&text).context("Failed to parse action.yml")?;
+        let using = mapping_get(&yaml, "runs")
+            .and_then(|runs| mapping_get(runs, "using"))
+            .and_then(|v| v.as_str());
+
+        return Ok(using
+            .filter(|rt| RETIRED_RUNTIMES.contains(rt))
+            .map(|rt| rt.to_string()));
+    }
+
+    Ok(None)
+}
+
+/// Lint a single workflow file for deprecated commands and retired action runtimes, printing
+/// diagnostics with file/line context. Returns `true` if anything was found. When `fix` is set,
+/// deprecated commands are rewritten in place (retired runtimes still require a human to pick and
+/// pin a newer release, so `--fix` can't resolve those).
+fn lint_workflow_file(file_path: &PathBuf, client: &reqwest::blocking::Client, fix: bool) -> Result<bool> {
+    let content =
+        fs::read_to_string(file_path).context(format!("Failed to read file: {:?}", file_path))?;
+
+    let mut found_any = false;
+
+    let deprecated = find_deprecated_commands(&content);
+    for (line_num, line_text) in &deprecated {
+        found_any = true;
+        println!(
+            "  {} {}:{}: deprecated workflow command: {}",
+            "LINT".yellow(),
+            file_path.display(),
+            line_num,
+            line_text
+        );
+    }
+
+    for pinned in find_pinned_references(&content)? {
+        match check_runtime_deprecation(client, &pinned.identifier, &pinned.sha) {
+            Ok(Some(runtime)) => {
+                found_any = true;
+                println!(
+                    "  {} {}: {}@{} uses retired runtime `{}`; bump to a release built on a supported runtime",
+                    "LINT".yellow(),
+                    file_path.display(),
+                    pinned.identifier,
+                    pinned.tag_comment,
+                    runtime
+                );
+            }
+            Ok(None) => {}
+            Err(e) => {
+                eprintln!(
+                    "  Warning: could not check runtime for {}: {}",
+                    pinned.identifier, e
+                );
+            }
+        }
+    }
+
+    if fix && !deprecated.is_empty() {
+        let (fixed_content, changed) = fix_deprecated_commands(&content);
+        if changed > 0 {
+            fs::write(file_path, fixed_content)
+                .context(format!("Failed to write file: {:?}", file_path))?;
+            println!("  Fixed {} deprecated command(s) in {}", changed, file_path.display());
+        }
+    }
+
+    Ok(found_any)
 }
 
-fn find_action_references(content: &str) -> Result<Vec<ActionReference>> {
+fn find_action_references(content: &str) -> Result<Vec<PinTarget>> {
     let yaml: Value = serde_yaml::from_str(content).context("Failed to parse YAML")?;
+    let lines: Vec<&str> = content.lines().collect();
 
     let mut refs = Vec::new();
-    extract_uses_from_value(&yaml, &mut refs);
+    let mut cursor = 0usize;
+    extract_uses_from_value(&yaml, &mut refs, &lines, &mut cursor);
     Ok(refs)
 }
 
-fn extract_uses_from_value(value: &Value, refs: &mut Vec<ActionReference>) {
+/// Walk the parsed YAML tree collecting `uses:`/`image:` targets in document order. `lines` and
+/// `cursor` are only needed for the already-pinned-SHA case: `serde_yaml` strips a scalar's
+/// trailing comment, so recovering the version comment a SHA pin is meant to track requires going
+/// back to the raw source line (see [`find_pinned_action_on_line`]).
+fn extract_uses_from_value(value: &Value, refs: &mut Vec<PinTarget>, lines: &[&str], cursor: &mut usize) {
     match value {
         Value::Mapping(map) => {
             for (key, val) in map {
                 if let Some(key_str) = key.as_str() {
                     if key_str == "uses" {
                         if let Some(uses_str) = val.as_str() {
-                            if let Some(action_ref) = parse_uses_string(uses_str) {
-                                refs.push(action_ref);
+                            if let Some(image_ref) = parse_docker_uses_string(uses_str) {
+                                refs.push(PinTarget::Image(image_ref));
+                            } else if let Some(action_ref) = parse_uses_string(uses_str) {
+                                refs.push(PinTarget::Action(action_ref));
+                            } else if let Some((identifier, sha)) = parse_pinned_identifier_and_sha(uses_str) {
+                                if let Some(pinned) =
+                                    find_pinned_action_on_line(lines, cursor, &identifier, &sha)
+                                {
+                                    refs.push(PinTarget::PinnedAction(pinned));
+                                }
+                            }
+                        }
+                    } else if key_str == "image" {
+                        if let Some(image_str) = val.as_str() {
+                            if let Some(image_ref) = parse_image_string(image_str) {
+                                refs.push(PinTarget::Image(image_ref));
                             }
                         }
                     }
                 }
-                extract_uses_from_value(val, refs);
+                extract_uses_from_value(val, refs, lines, cursor);
             }
         }
         Value::Sequence(seq) => {
             for item in seq {
-                extract_uses_from_value(item, refs);
+                extract_uses_from_value(item, refs, lines, cursor);
             }
         }
         _ => {}
     }
 }
 
+/// Recognize an already-SHA-pinned `owner/repo@<sha>` value (as seen by the YAML tree walk, with
+/// any trailing comment already stripped by the parser) without needing the comment.
+fn parse_pinned_identifier_and_sha(uses: &str) -> Option<(String, String)> {
+    let (identifier, sha) = uses.trim().rsplit_once('@')?;
+    if !(sha.len() == 40 && sha.chars().all(|c| c.is_ascii_hexdigit())) {
+        return None;
+    }
+    Some((identifier.to_string(), sha.to_string()))
+}
+
 fn parse_uses_string(uses: &str) -> Option<ActionReference> {
     // Parse "owner/repo@reference" format
     // Extract just the part before any comment
@@ -175,9 +1301,10 @@ fn parse_uses_string(uses: &str) -> Option<ActionReference> {
     }
 
     let reference = parts[1].trim().to_string();
+    let kind = GitReference::classify(&reference);
 
-    // Skip if already pinned to a SHA (40 hex chars)
-    if reference.len() == 40 && reference.chars().all(|c| c.is_ascii_hexdigit()) {
+    // Skip if already pinned to a SHA
+    if matches!(kind, GitReference::Rev(_)) {
         return None;
     }
 
@@ -193,23 +1320,323 @@ fn parse_uses_string(uses: &str) -> Option<ActionReference> {
         owner,
         repo,
         reference,
+        kind,
     })
 }
 
-fn resolve_reference(action_ref: &ActionReference, update: bool) -> Result<(String, String)> {
-    let client = reqwest::blocking::Client::builder()
-        .user_agent("pin-and-bump/0.1.0")
-        .build()?;
+/// The `# ...` comment to write back for a resolved reference, reflecting its real kind rather
+/// than always looking like a release tag.
+fn version_comment(kind: &GitReference) -> String {
+    match kind {
+        GitReference::Branch(name) => {
+            format!("branch {} @ {}", name, chrono::Utc::now().format("%Y-%m-%d"))
+        }
+        GitReference::Tag(tag) | GitReference::MajorFloat(tag) | GitReference::Rev(tag) => {
+            tag.clone()
+        }
+    }
+}
+
+/// Parse a `uses: docker://image:tag` step reference into an [`ImageReference`].
+fn parse_docker_uses_string(uses: &str) -> Option<ImageReference> {
+    let uses_clean = uses.split('#').next()?.trim();
+    let image = uses_clean.strip_prefix("docker://")?;
+    parse_image_string_inner(image, true)
+}
+
+/// Parse an `image: name:tag` value (as used by `container:` and `services:`) into an
+/// [`ImageReference`].
+fn parse_image_string(image: &str) -> Option<ImageReference> {
+    let image_clean = image.split('#').next()?.trim();
+    parse_image_string_inner(image_clean, false)
+}
+
+fn parse_image_string_inner(image: &str, is_docker_uses: bool) -> Option<ImageReference> {
+    // Already pinned to a digest; nothing to do.
+    if image.contains('@') {
+        return None;
+    }
+
+    let (repo_and_registry, tag) = image.rsplit_once(':')?;
+
+    // A `:` can also appear as part of a registry port (e.g. `localhost:5000/img`), which
+    // rsplit_once would misparse as repository `localhost` with tag `5000/img`. Guard against that by
+    // requiring the tag half to contain no `/`.
+    if tag.contains('/') {
+        return None;
+    }
+
+    let (registry, repository) = split_registry_and_repo(repo_and_registry);
+
+    Some(ImageReference {
+        registry,
+        repository,
+        tag: tag.to_string(),
+        is_docker_uses,
+    })
+}
+
+/// Split an image name like `ghcr.io/org/img` into its registry host (if any) and repository
+/// path. A leading path segment is treated as a registry host if it contains a `.` or `:`, or is
+/// literally `localhost` — mirroring how the Docker CLI disambiguates `registry/repo` from a
+/// bare Docker Hub repository like `library/img`.
+fn split_registry_and_repo(image: &str) -> (Option<String>, String) {
+    match image.split_once('/') {
+        Some((first, rest)) if first.contains('.') || first.contains(':') || first == "localhost" => {
+            (Some(first.to_string()), rest.to_string())
+        }
+        _ => (None, image.to_string()),
+    }
+}
+
+/// Resolve the registry host and repository path to query for a given image, filling in the
+/// Docker Hub default and its `library/` namespace for bare image names.
+fn registry_host_and_repo(image_ref: &ImageReference) -> (String, String) {
+    match &image_ref.registry {
+        Some(host) => (host.clone(), image_ref.repository.clone()),
+        None => {
+            let repository = if image_ref.repository.contains('/') {
+                image_ref.repository.clone()
+            } else {
+                format!("library/{}", image_ref.repository)
+            };
+            ("registry-1.docker.io".to_string(), repository)
+        }
+    }
+}
+
+const MANIFEST_ACCEPT_HEADER: &str = "application/vnd.docker.distribution.manifest.list.v2+json, application/vnd.docker.distribution.manifest.v2+json, application/vnd.oci.image.index.v1+json, application/vnd.oci.image.manifest.v1+json";
+
+/// Resolve `registry_host/repository:tag` to its content digest via the OCI Distribution
+/// manifest endpoint, handling the Bearer auth handshake on a `401` challenge.
+fn resolve_image_digest(
+    client: &reqwest::blocking::Client,
+    registry_host: &str,
+    repository: &str,
+    tag: &str,
+) -> Result<String> {
+    resolve_image_digest_with_base(client, &format!("https://{}", registry_host), repository, tag)
+}
+
+/// Same as [`resolve_image_digest`], but against an arbitrary base URL instead of always `https://
+/// <registry_host>` — split out so tests can point it at a mockito server over plain HTTP.
+fn resolve_image_digest_with_base(
+    client: &reqwest::blocking::Client,
+    base_url: &str,
+    repository: &str,
+    tag: &str,
+) -> Result<String> {
+    let manifest_url = format!("{}/v2/{}/manifests/{}", base_url, repository, tag);
+
+    let response = client
+        .head(&manifest_url)
+        .header("Accept", MANIFEST_ACCEPT_HEADER)
+        .send()?;
+
+    let mut bearer_token: Option<String> = None;
+    let response = if response.status() == reqwest::StatusCode::UNAUTHORIZED {
+        let token = authenticate_with_registry(client, &response, repository)?;
+        let response = client
+            .head(&manifest_url)
+            .header("Accept", MANIFEST_ACCEPT_HEADER)
+            .header("Authorization", format!("Bearer {}", token))
+            .send()?;
+        bearer_token = Some(token);
+        response
+    } else {
+        response
+    };
+
+    // Some registries respond poorly to HEAD; fall back to GET for the digest header, carrying
+    // over the Bearer token from above if we had to authenticate — otherwise a registry that
+    // requires auth for both HEAD and GET just gets another 401 here.
+    let response = if response.status().is_success() {
+        response
+    } else {
+        let mut request = client.get(&manifest_url).header("Accept", MANIFEST_ACCEPT_HEADER);
+        if let Some(token) = &bearer_token {
+            request = request.header("Authorization", format!("Bearer {}", token));
+        }
+        request.send()?
+    };
+
+    if !response.status().is_success() {
+        anyhow::bail!(
+            "Could not resolve manifest digest: HTTP {}",
+            response.status()
+        );
+    }
 
-    resolve_reference_with_client(action_ref, update, &client, "https://api.github.com")
+    response
+        .headers()
+        .get("Docker-Content-Digest")
+        .context("registry did not return a Docker-Content-Digest header")?
+        .to_str()
+        .context("Docker-Content-Digest header was not valid UTF-8")
+        .map(|s| s.to_string())
+}
+
+#[derive(Debug, Deserialize)]
+struct RegistryTokenResponse {
+    token: Option<String>,
+    access_token: Option<String>,
+}
+
+/// Parse a `401` response's `WWW-Authenticate: Bearer realm=...,service=...,scope=...` header,
+/// fetch a token from the realm, and return it for use as an `Authorization: Bearer` header.
+fn authenticate_with_registry(
+    client: &reqwest::blocking::Client,
+    unauthorized_response: &reqwest::blocking::Response,
+    repository: &str,
+) -> Result<String> {
+    let www_authenticate = unauthorized_response
+        .headers()
+        .get(reqwest::header::WWW_AUTHENTICATE)
+        .context("registry returned 401 without a WWW-Authenticate header")?
+        .to_str()
+        .context("WWW-Authenticate header was not valid UTF-8")?;
+
+    let (realm, service, scope) = parse_www_authenticate(www_authenticate, repository)?;
+
+    let mut token_url = format!("{}?service={}", realm, service);
+    if let Some(scope) = scope {
+        token_url.push_str(&format!("&scope={}", scope));
+    }
+
+    let token_response: RegistryTokenResponse = client.get(&token_url).send()?.json()?;
+
+    token_response
+        .token
+        .or(token_response.access_token)
+        .context("registry token response contained no token")
+}
+
+/// Parse the `Bearer realm="...",service="...",scope="..."` challenge into its components. If
+/// `scope` is omitted by the server, default it to `repository:<repository>:pull`.
+fn parse_www_authenticate(
+    header: &str,
+    repository: &str,
+) -> Result<(String, String, Option<String>)> {
+    let rest = header
+        .strip_prefix("Bearer ")
+        .context("unsupported WWW-Authenticate scheme")?;
+
+    let mut realm = None;
+    let mut service = None;
+    let mut scope = None;
+
+    for part in rest.split(',') {
+        let (key, value) = part.trim().split_once('=').unwrap_or((part.trim(), ""));
+        let value = value.trim().trim_matches('"').to_string();
+        match key {
+            "realm" => realm = Some(value),
+            "service" => service = Some(value),
+            "scope" => scope = Some(value),
+            _ => {}
+        }
+    }
+
+    let realm = realm.context("WWW-Authenticate header missing realm")?;
+    let service = service.unwrap_or_default();
+    let scope = scope.or_else(|| Some(format!("repository:{}:pull", repository)));
+
+    Ok((realm, service, scope))
+}
+
+/// Maximum number of rate-limit retries before giving up and returning the last response as-is.
+const MAX_RATE_LIMIT_RETRIES: u32 = 5;
+
+/// Send a request, retrying with jittered backoff if GitHub responds `403`/`429` and its
+/// rate-limit headers say we're out of quota. Sleeps until `X-RateLimit-Reset` (falling back to
+/// exponential backoff if that header is absent) so concurrent resolution doesn't hammer an
+/// already-exhausted rate limit.
+fn send_with_rate_limit_retry(
+    request: reqwest::blocking::RequestBuilder,
+) -> Result<reqwest::blocking::Response> {
+    let mut attempt = 0;
+
+    loop {
+        let pending = request
+            .try_clone()
+            .context("request could not be retried (streaming body)")?;
+        let response = pending.send()?;
+        let status = response.status();
+
+        let is_rate_limited = status == reqwest::StatusCode::FORBIDDEN
+            || status == reqwest::StatusCode::TOO_MANY_REQUESTS;
+        let remaining_is_zero = response
+            .headers()
+            .get("x-ratelimit-remaining")
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.parse::<u64>().ok())
+            == Some(0);
+
+        if !is_rate_limited || attempt >= MAX_RATE_LIMIT_RETRIES || (!remaining_is_zero && status == reqwest::StatusCode::FORBIDDEN) {
+            return Ok(response);
+        }
+
+        let wait = rate_limit_backoff(&response, attempt);
+        eprintln!(
+            "  Rate limited (HTTP {}); waiting {:.0}s before retrying...",
+            status,
+            wait.as_secs_f64()
+        );
+        std::thread::sleep(wait);
+        attempt += 1;
+    }
+}
+
+/// How long to wait before retrying a rate-limited request: until `X-RateLimit-Reset` if GitHub
+/// sent one, otherwise exponential backoff from the attempt count. Either way, jitter by up to
+/// 20% so a bunch of concurrently-resolving requests don't all wake up and retry at once.
+fn rate_limit_backoff(response: &reqwest::blocking::Response, attempt: u32) -> Duration {
+    let base_secs = response
+        .headers()
+        .get("x-ratelimit-reset")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<u64>().ok())
+        .map(|reset_epoch| {
+            let now_epoch = std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0);
+            reset_epoch.saturating_sub(now_epoch)
+        })
+        .unwrap_or_else(|| 2u64.saturating_pow(attempt.min(6)));
+
+    let jitter_fraction = rand::thread_rng().gen_range(0.0..0.2);
+    Duration::from_secs_f64(base_secs as f64 * (1.0 + jitter_fraction))
+}
+
+/// Read a response's `ETag` header, if present, for persistence in the lockfile.
+fn etag_header(response: &reqwest::blocking::Response) -> Option<String> {
+    response
+        .headers()
+        .get(reqwest::header::ETAG)
+        .and_then(|v| v.to_str().ok())
+        .map(|s| s.to_string())
+}
+
+/// Attach `If-None-Match` to a request when a previous resolution of this exact reference left
+/// behind an `ETag`, so an unchanged reference costs a `304` instead of a full lookup.
+fn with_conditional_header(
+    request: reqwest::blocking::RequestBuilder,
+    cached: Option<&LockEntry>,
+) -> reqwest::blocking::RequestBuilder {
+    match cached.and_then(|entry| entry.etag.as_ref()) {
+        Some(etag) => request.header(reqwest::header::IF_NONE_MATCH, etag),
+        None => request,
+    }
 }
 
 fn resolve_reference_with_client(
     action_ref: &ActionReference,
     update: bool,
+    allow_branches: bool,
     client: &reqwest::blocking::Client,
     base_url: &str,
-) -> Result<(String, String)> {
+    cached: Option<&LockEntry>,
+) -> Result<(String, GitReference, Option<String>)> {
     if update {
         // Get latest release or tag
         let latest_url = format!(
@@ -222,22 +1649,36 @@ fn resolve_reference_with_client(
             tag_name: String,
         }
 
-        let response = client.get(&latest_url).send();
+        let request = with_conditional_header(client.get(&latest_url), cached);
+        let response = send_with_rate_limit_retry(request);
 
         match response {
+            Ok(resp) if resp.status() == reqwest::StatusCode::NOT_MODIFIED => {
+                if let Some(entry) = cached {
+                    return Ok((
+                        entry.resolved.clone(),
+                        GitReference::classify(&entry.resolved_tag),
+                        entry.etag.clone(),
+                    ));
+                }
+                // A 304 with nothing cached to reuse shouldn't happen; fall through and
+                // re-resolve the current reference instead of erroring.
+            }
             Ok(resp) if resp.status().is_success() => {
+                let etag = etag_header(&resp);
                 let release: Release = resp.json()?;
-                let tag = &release.tag_name;
+                let kind = GitReference::classify(&release.tag_name);
 
-                // Now get the SHA for this tag
-                let sha = get_sha_for_ref_with_base(
+                let (sha, kind, _etag) = get_sha_for_kind(
                     client,
                     base_url,
                     &action_ref.owner,
                     &action_ref.repo,
-                    tag,
+                    &kind,
+                    allow_branches,
+                    None,
                 )?;
-                return Ok((sha, tag.clone()));
+                return Ok((sha, kind, etag));
             }
             _ => {
                 // Fall back to getting SHA for the current reference
@@ -246,71 +1687,211 @@ fn resolve_reference_with_client(
     }
 
     // Get SHA for the current reference
-    let sha = get_sha_for_ref_with_base(
+    get_sha_for_kind(
         client,
         base_url,
         &action_ref.owner,
         &action_ref.repo,
-        &action_ref.reference,
-    )?;
-    Ok((sha, action_ref.reference.clone()))
+        &action_ref.kind,
+        allow_branches,
+        cached,
+    )
+}
+
+/// Under `--update`, check whether `identifier` (`owner/repo`) has a release newer than whatever
+/// it's currently pinned to. Returns `Ok(None)` if there's no release to move to (no releases at
+/// all, or the lookup fails) — the caller then leaves the existing pin untouched.
+fn resolve_latest_for_pinned_action(
+    client: &reqwest::blocking::Client,
+    base_url: &str,
+    identifier: &str,
+) -> Result<Option<(String, String)>> {
+    let (owner, repo) = identifier
+        .split_once('/')
+        .context(format!("`{}` is not an owner/repo identifier", identifier))?;
+
+    #[derive(Deserialize)]
+    struct Release {
+        tag_name: String,
+    }
+
+    let latest_url = format!("{}/repos/{}/releases/latest", base_url, identifier);
+    let response = send_with_rate_limit_retry(client.get(&latest_url))?;
+    if !response.status().is_success() {
+        return Ok(None);
+    }
+
+    let release: Release = response.json()?;
+    let kind = GitReference::classify(&release.tag_name);
+    let (sha, kind, _etag) = get_sha_for_kind(client, base_url, owner, repo, &kind, false, None)?;
+
+    Ok(Some((sha, version_comment(&kind))))
+}
+
+/// Resolve a classified reference to a commit SHA, driving resolution per-kind rather than
+/// guessing tag-then-commit. Falling back from a tag lookup to the commits endpoint means the
+/// reference is actually a branch; that's only honored under `allow_branches`, and the returned
+/// kind is corrected to [`GitReference::Branch`] so the caller writes an honest comment.
+/// `cached` carries a previous resolution's `ETag`, if any, so an unchanged reference can be
+/// confirmed with a `304` instead of a full re-resolution.
+fn get_sha_for_kind(
+    client: &reqwest::blocking::Client,
+    base_url: &str,
+    owner: &str,
+    repo: &str,
+    kind: &GitReference,
+    allow_branches: bool,
+    cached: Option<&LockEntry>,
+) -> Result<(String, GitReference, Option<String>)> {
+    match kind {
+        GitReference::Rev(sha) => Ok((sha.clone(), kind.clone(), None)),
+        GitReference::Branch(branch) => {
+            if !allow_branches {
+                anyhow::bail!(
+                    "`{}` is a branch, not a tag; pinning it is not reproducible. Re-run with --allow-branches to pin it anyway",
+                    branch
+                );
+            }
+            match get_commit_sha_for_ref(client, base_url, owner, repo, branch, cached)? {
+                RefLookup::NotModified(sha, etag) => Ok((sha, kind.clone(), etag)),
+                RefLookup::Resolved(sha, etag) => Ok((sha, kind.clone(), etag)),
+            }
+        }
+        GitReference::Tag(tag) | GitReference::MajorFloat(tag) => {
+            match get_commit_sha_for_tag(client, base_url, owner, repo, tag, cached)? {
+                TagLookup::NotModified(sha, etag) => Ok((sha, kind.clone(), etag)),
+                TagLookup::Resolved(sha, etag) => Ok((sha, kind.clone(), etag)),
+                TagLookup::NotATag => {
+                    // Not a tag after all; the fallback family pin would otherwise silently
+                    // treat a moving branch as a release.
+                    if !allow_branches {
+                        anyhow::bail!(
+                            "`{}` is not a tag on {}/{} (it looks like a branch); re-run with --allow-branches to pin it anyway",
+                            tag,
+                            owner,
+                            repo
+                        );
+                    }
+                    eprintln!(
+                        "  Warning: `{}` on {}/{} is a branch, not a tag; pinning its tip is not reproducible",
+                        tag, owner, repo
+                    );
+                    match get_commit_sha_for_ref(client, base_url, owner, repo, tag, cached)? {
+                        RefLookup::NotModified(sha, etag) => {
+                            Ok((sha, GitReference::Branch(tag.clone()), etag))
+                        }
+                        RefLookup::Resolved(sha, etag) => {
+                            Ok((sha, GitReference::Branch(tag.clone()), etag))
+                        }
+                    }
+                }
+            }
+        }
+    }
 }
 
-fn get_sha_for_ref_with_base(
+/// The result of looking up a tag name against the tags endpoint.
+enum TagLookup {
+    /// `ref_name` is not a tag at all (the caller should try branch resolution).
+    NotATag,
+    /// The cached `ETag` was still valid; GitHub returned `304` and the previously-resolved SHA
+    /// still applies.
+    NotModified(String, Option<String>),
+    /// Freshly resolved to a commit SHA, with the `ETag` to cache for next time.
+    Resolved(String, Option<String>),
+}
+
+/// Resolve a tag name to the SHA of the commit it points at, dereferencing annotated tag
+/// objects. Returns [`TagLookup::NotATag`] (rather than erroring) when `ref_name` is not a tag
+/// at all, so the caller can fall back to branch resolution.
+fn get_commit_sha_for_tag(
     client: &reqwest::blocking::Client,
     base_url: &str,
     owner: &str,
     repo: &str,
     ref_name: &str,
-) -> Result<String> {
+    cached: Option<&LockEntry>,
+) -> Result<TagLookup> {
     let url = format!(
         "{}/repos/{}/{}/git/ref/tags/{}",
         base_url, owner, repo, ref_name
     );
 
-    let response = client.get(&url).send()?;
+    let request = with_conditional_header(client.get(&url), cached);
+    let response = send_with_rate_limit_retry(request)?;
 
-    if response.status().is_success() {
-        let tag: GitHubTag = response.json()?;
-
-        // Tags can point to tag objects or commits directly
-        // If it's a tag object, we need to dereference it
-        let commit_sha = if tag.object.sha.len() == 40 {
-            // Try to get the commit this tag points to
-            let commit_url = format!(
-                "{}/repos/{}/{}/git/tags/{}",
-                base_url, owner, repo, tag.object.sha
-            );
+    if response.status() == reqwest::StatusCode::NOT_MODIFIED {
+        if let Some(entry) = cached {
+            return Ok(TagLookup::NotModified(entry.resolved.clone(), entry.etag.clone()));
+        }
+    }
 
-            #[derive(Deserialize)]
-            struct TagObject {
-                object: GitHubObject,
-            }
+    if !response.status().is_success() {
+        return Ok(TagLookup::NotATag);
+    }
 
-            match client.get(&commit_url).send() {
-                Ok(resp) if resp.status().is_success() => {
-                    let tag_obj: TagObject = resp.json()?;
-                    tag_obj.object.sha
-                }
-                _ => tag.object.sha,
-            }
-        } else {
-            tag.object.sha
-        };
+    let etag = etag_header(&response);
+    let tag: GitHubTag = response.json()?;
 
-        Ok(commit_sha)
-    } else {
-        // Try as a branch or direct commit reference
-        let url = format!("{}/repos/{}/{}/commits/{}", base_url, owner, repo, ref_name);
+    // An annotated tag's ref points at a tag *object*, whose id is not the commit id; a
+    // lightweight tag's ref points directly at the commit. Try to dereference as a tag object
+    // first and fall back to treating the id as the commit SHA if that 404s.
+    let commit_url = format!(
+        "{}/repos/{}/{}/git/tags/{}",
+        base_url, owner, repo, tag.object.sha
+    );
 
-        let response = client.get(&url).send()?;
+    #[derive(Deserialize)]
+    struct TagObject {
+        object: GitHubObject,
+    }
 
-        if response.status().is_success() {
-            let commit: GitHubCommit = response.json()?;
-            Ok(commit.sha)
-        } else {
-            anyhow::bail!("Could not resolve reference: HTTP {}", response.status())
+    let commit_sha = match send_with_rate_limit_retry(client.get(&commit_url)) {
+        Ok(resp) if resp.status().is_success() => {
+            let tag_obj: TagObject = resp.json()?;
+            tag_obj.object.sha
         }
+        _ => tag.object.sha,
+    };
+
+    Ok(TagLookup::Resolved(commit_sha, etag))
+}
+
+/// The result of looking up a ref (typically a branch) against the commits endpoint.
+enum RefLookup {
+    /// The cached `ETag` was still valid; GitHub returned `304` and the previously-resolved SHA
+    /// still applies.
+    NotModified(String, Option<String>),
+    /// Freshly resolved to a commit SHA, with the `ETag` to cache for next time.
+    Resolved(String, Option<String>),
+}
+
+/// Resolve a branch name (or any other non-tag ref) to the SHA of its current tip commit.
+fn get_commit_sha_for_ref(
+    client: &reqwest::blocking::Client,
+    base_url: &str,
+    owner: &str,
+    repo: &str,
+    ref_name: &str,
+    cached: Option<&LockEntry>,
+) -> Result<RefLookup> {
+    let url = format!("{}/repos/{}/{}/commits/{}", base_url, owner, repo, ref_name);
+
+    let request = with_conditional_header(client.get(&url), cached);
+    let response = send_with_rate_limit_retry(request)?;
+
+    if response.status() == reqwest::StatusCode::NOT_MODIFIED {
+        if let Some(entry) = cached {
+            return Ok(RefLookup::NotModified(entry.resolved.clone(), entry.etag.clone()));
+        }
+    }
+
+    if response.status().is_success() {
+        let etag = etag_header(&response);
+        let commit: GitHubCommit = response.json()?;
+        Ok(RefLookup::Resolved(commit.sha, etag))
+    } else {
+        anyhow::bail!("Could not resolve reference: HTTP {}", response.status())
     }
 }
 
@@ -318,6 +1899,20 @@ fn get_sha_for_ref_with_base(
 mod tests {
     use super::*;
 
+    fn as_action(target: &PinTarget) -> &ActionReference {
+        match target {
+            PinTarget::Action(action_ref) => action_ref,
+            other => panic!("expected an action reference, got {:?}", other),
+        }
+    }
+
+    fn as_image(target: &PinTarget) -> &ImageReference {
+        match target {
+            PinTarget::Image(image_ref) => image_ref,
+            other => panic!("expected an image reference, got {:?}", other),
+        }
+    }
+
     #[test]
     fn test_find_action_references() {
         let input = r#"
@@ -333,22 +1928,63 @@ jobs:
 
         let refs = find_action_references(input).unwrap();
 
-        assert_eq!(refs.len(), 3);
-        assert_eq!(refs[0].owner, "actions");
-        assert_eq!(refs[0].repo, "checkout");
-        assert_eq!(refs[0].reference, "v4");
-
-        assert_eq!(refs[1].owner, "actions");
-        assert_eq!(refs[1].repo, "setup-go");
-        assert_eq!(refs[1].reference, "v5");
-
-        assert_eq!(refs[2].owner, "docker");
-        assert_eq!(refs[2].repo, "setup-buildx-action");
-        assert_eq!(refs[2].reference, "v3.0.0");
+        assert_eq!(refs.len(), 3);
+        assert_eq!(as_action(&refs[0]).owner, "actions");
+        assert_eq!(as_action(&refs[0]).repo, "checkout");
+        assert_eq!(as_action(&refs[0]).reference, "v4");
+
+        assert_eq!(as_action(&refs[1]).owner, "actions");
+        assert_eq!(as_action(&refs[1]).repo, "setup-go");
+        assert_eq!(as_action(&refs[1]).reference, "v5");
+
+        assert_eq!(as_action(&refs[2]).owner, "docker");
+        assert_eq!(as_action(&refs[2]).repo, "setup-buildx-action");
+        assert_eq!(as_action(&refs[2]).reference, "v3.0.0");
+    }
+
+    #[test]
+    fn test_finds_docker_image_references() {
+        let input = r#"
+jobs:
+  test:
+    container:
+      image: node:18
+    services:
+      db:
+        image: postgres:15-alpine
+    steps:
+      - uses: docker://ghcr.io/org/img:v1.2.3
+      - uses: actions/checkout@v4
+"#;
+
+        let refs = find_action_references(input).unwrap();
+        let images: Vec<&ImageReference> = refs
+            .iter()
+            .filter(|t| matches!(t, PinTarget::Image(_)))
+            .map(as_image)
+            .collect();
+
+        assert_eq!(images.len(), 3);
+
+        let node = images.iter().find(|i| i.repository == "node").unwrap();
+        assert_eq!(node.registry, None);
+        assert_eq!(node.tag, "18");
+        assert!(!node.is_docker_uses);
+
+        let ghcr = images.iter().find(|i| i.repository == "org/img").unwrap();
+        assert_eq!(ghcr.registry.as_deref(), Some("ghcr.io"));
+        assert_eq!(ghcr.tag, "v1.2.3");
+        assert!(ghcr.is_docker_uses);
+    }
+
+    #[test]
+    fn test_skips_digest_pinned_images() {
+        let input = "image: alpine@sha256:abcd1234";
+        assert!(parse_image_string(input).is_none());
     }
 
     #[test]
-    fn test_skips_already_pinned_shas() {
+    fn test_already_pinned_sha_is_a_pinned_action_target() {
         let input = r#"
 jobs:
   test:
@@ -357,7 +1993,16 @@ jobs:
 "#;
 
         let refs = find_action_references(input).unwrap();
-        assert_eq!(refs.len(), 0);
+        assert_eq!(refs.len(), 1);
+
+        match &refs[0] {
+            PinTarget::PinnedAction(pinned) => {
+                assert_eq!(pinned.identifier, "actions/checkout");
+                assert_eq!(pinned.sha, "8ade135a41bc03ea155e62e844d188df1ea18608");
+                assert_eq!(pinned.tag_comment, "v4");
+            }
+            other => panic!("expected a pinned action target, got {:?}", other),
+        }
     }
 
     #[test]
@@ -378,15 +2023,17 @@ jobs:
             owner: "actions".to_string(),
             repo: "checkout".to_string(),
             reference: "v4".to_string(),
+            kind: GitReference::classify("v4"),
         };
 
         let client = reqwest::blocking::Client::new();
-        let result = resolve_reference_with_client(&action_ref, false, &client, &server.url());
+        let result =
+            resolve_reference_with_client(&action_ref, false, false, &client, &server.url(), None);
 
         assert!(result.is_ok());
-        let (sha, tag) = result.unwrap();
+        let (sha, kind, _etag) = result.unwrap();
         assert_eq!(sha, "8ade135a41bc03ea155e62e844d188df1ea18608");
-        assert_eq!(tag, "v4");
+        assert_eq!(kind, GitReference::MajorFloat("v4".to_string()));
     }
 
     #[test]
@@ -439,18 +2086,23 @@ jobs:
         let client = reqwest::blocking::Client::new();
         let mut updated_content = content.clone();
 
-        for action_ref in action_refs {
-            let result = resolve_reference_with_client(&action_ref, false, &client, &server.url());
+        for target in action_refs {
+            let action_ref = as_action(&target);
+            let result =
+                resolve_reference_with_client(action_ref, false, false, &client, &server.url(), None);
             assert!(result.is_ok());
 
-            let (sha, version_tag) = result.unwrap();
+            let (sha, resolved_kind, _etag) = result.unwrap();
             let old_uses = format!(
                 "{}/{}@{}",
                 action_ref.owner, action_ref.repo, action_ref.reference
             );
             let new_uses = format!(
                 "{}/{}@{} # {}",
-                action_ref.owner, action_ref.repo, sha, version_tag
+                action_ref.owner,
+                action_ref.repo,
+                sha,
+                version_comment(&resolved_kind)
             );
 
             updated_content = updated_content.replace(
@@ -498,15 +2150,17 @@ jobs:
             owner: "actions".to_string(),
             repo: "checkout".to_string(),
             reference: "v4".to_string(),
+            kind: GitReference::classify("v4"),
         };
 
         let client = reqwest::blocking::Client::new();
-        let result = resolve_reference_with_client(&action_ref, true, &client, &server.url());
+        let result =
+            resolve_reference_with_client(&action_ref, true, false, &client, &server.url(), None);
 
         assert!(result.is_ok());
-        let (sha, tag) = result.unwrap();
+        let (sha, kind, _etag) = result.unwrap();
         assert_eq!(sha, "11111111111111111111111111111111111111ab");
-        assert_eq!(tag, "v4.2.0"); // Should be updated to latest version
+        assert_eq!(kind, GitReference::Tag("v4.2.0".to_string())); // Should be updated to latest version
     }
 
     #[test]
@@ -533,15 +2187,78 @@ jobs:
             owner: "actions".to_string(),
             repo: "checkout".to_string(),
             reference: "v4".to_string(),
+            kind: GitReference::classify("v4"),
         };
 
         let client = reqwest::blocking::Client::new();
-        let result = resolve_reference_with_client(&action_ref, true, &client, &server.url());
+        let result =
+            resolve_reference_with_client(&action_ref, true, false, &client, &server.url(), None);
 
         assert!(result.is_ok());
-        let (sha, tag) = result.unwrap();
+        let (sha, kind, _etag) = result.unwrap();
         assert_eq!(sha, "8ade135a41bc03ea155e62e844d188df1ea18608");
-        assert_eq!(tag, "v4"); // Should fall back to current reference
+        assert_eq!(kind, GitReference::MajorFloat("v4".to_string())); // Should fall back to current reference
+    }
+
+    #[test]
+    fn test_branch_reference_requires_allow_branches_flag() {
+        use mockito::Server;
+
+        let mut server = Server::new();
+
+        // Not a tag.
+        let _mock_tag = server
+            .mock("GET", "/repos/actions/checkout/git/ref/tags/main")
+            .with_status(404)
+            .create();
+
+        let action_ref = ActionReference {
+            owner: "actions".to_string(),
+            repo: "checkout".to_string(),
+            reference: "main".to_string(),
+            kind: GitReference::classify("main"),
+        };
+
+        let client = reqwest::blocking::Client::new();
+        let result =
+            resolve_reference_with_client(&action_ref, false, false, &client, &server.url(), None);
+
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_branch_reference_resolves_with_allow_branches_flag() {
+        use mockito::Server;
+
+        let mut server = Server::new();
+
+        let _mock_tag = server
+            .mock("GET", "/repos/actions/checkout/git/ref/tags/main")
+            .with_status(404)
+            .create();
+
+        let _mock_commit = server
+            .mock("GET", "/repos/actions/checkout/commits/main")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(r#"{"sha": "2222222222222222222222222222222222222cd"}"#)
+            .create();
+
+        let action_ref = ActionReference {
+            owner: "actions".to_string(),
+            repo: "checkout".to_string(),
+            reference: "main".to_string(),
+            kind: GitReference::classify("main"),
+        };
+
+        let client = reqwest::blocking::Client::new();
+        let result =
+            resolve_reference_with_client(&action_ref, false, true, &client, &server.url(), None);
+
+        assert!(result.is_ok());
+        let (sha, kind, _etag) = result.unwrap();
+        assert_eq!(sha, "2222222222222222222222222222222222222cd");
+        assert_eq!(kind, GitReference::Branch("main".to_string()));
     }
 
     #[test]
@@ -599,7 +2316,506 @@ jobs:
 
         let refs = find_action_references(input).unwrap();
         assert_eq!(refs.len(), 4);
-        assert_eq!(refs[0].owner, "actions");
-        assert_eq!(refs[0].repo, "checkout");
+        assert_eq!(as_action(&refs[0]).owner, "actions");
+        assert_eq!(as_action(&refs[0]).repo, "checkout");
+    }
+
+    #[test]
+    fn test_lockfile_upsert_replaces_existing_entry() {
+        let mut lockfile = LockFile::default();
+        let key = LockKey {
+            kind: LockTargetKind::Action,
+            identifier: "actions/checkout".to_string(),
+            requested: "v4".to_string(),
+        };
+
+        lockfile.upsert(LockEntry {
+            key: key.clone(),
+            resolved: "aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa".to_string(),
+            resolved_tag: "v4.1.0".to_string(),
+            resolved_at: "2025-01-01T00:00:00Z".to_string(),
+            etag: None,
+        });
+        lockfile.upsert(LockEntry {
+            key: key.clone(),
+            resolved: "bbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbb".to_string(),
+            resolved_tag: "v4.2.0".to_string(),
+            resolved_at: "2025-02-01T00:00:00Z".to_string(),
+            etag: None,
+        });
+
+        assert_eq!(lockfile.entries.len(), 1);
+        assert_eq!(
+            lockfile.find(&key).unwrap().resolved,
+            "bbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbb"
+        );
+    }
+
+    #[test]
+    fn test_lockfile_roundtrips_through_json() {
+        let mut lockfile = LockFile::default();
+        lockfile.upsert(LockEntry {
+            key: LockKey {
+                kind: LockTargetKind::Image,
+                identifier: "registry-1.docker.io/library/node".to_string(),
+                requested: "18".to_string(),
+            },
+            resolved: "sha256:abcd".to_string(),
+            resolved_tag: "18".to_string(),
+            resolved_at: "2025-01-01T00:00:00Z".to_string(),
+            etag: None,
+        });
+
+        let serialized = serde_json::to_string(&lockfile).unwrap();
+        let deserialized: LockFile = serde_json::from_str(&serialized).unwrap();
+
+        assert_eq!(deserialized.entries.len(), 1);
+        assert_eq!(deserialized.entries[0].resolved, "sha256:abcd");
+    }
+
+    #[test]
+    fn test_parse_pinned_uses_string() {
+        let pinned =
+            parse_pinned_uses_string("actions/checkout@8ade135a41bc03ea155e62e844d188df1ea18608 # v4")
+                .unwrap();
+
+        assert_eq!(pinned.identifier, "actions/checkout");
+        assert_eq!(pinned.sha, "8ade135a41bc03ea155e62e844d188df1ea18608");
+        assert_eq!(pinned.tag_comment, "v4");
+
+        // Not yet pinned to a SHA, so nothing to verify.
+        assert!(parse_pinned_uses_string("actions/checkout@v4").is_none());
+    }
+
+    #[test]
+    fn test_verify_detects_mismatched_pin() {
+        use std::fs;
+        use tempfile::TempDir;
+
+        let mut lockfile = LockFile::default();
+        lockfile.upsert(LockEntry {
+            key: LockKey {
+                kind: LockTargetKind::Action,
+                identifier: "actions/checkout".to_string(),
+                requested: "v4".to_string(),
+            },
+            resolved: "aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa".to_string(),
+            resolved_tag: "v4".to_string(),
+            resolved_at: "2025-01-01T00:00:00Z".to_string(),
+            etag: None,
+        });
+
+        let workflow = r#"
+jobs:
+  test:
+    steps:
+      - uses: actions/checkout@bbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbb # v4
+"#;
+
+        let temp_dir = TempDir::new().unwrap();
+        let workflow_file = temp_dir.path().join("test.yaml");
+        fs::write(&workflow_file, workflow).unwrap();
+
+        let ok = verify_workflow_file(&workflow_file, &lockfile).unwrap();
+        assert!(!ok);
+    }
+
+    #[test]
+    fn test_tag_lookup_reuses_cached_sha_on_not_modified() {
+        use mockito::Server;
+
+        let mut server = Server::new();
+        let _mock = server
+            .mock("GET", "/repos/actions/checkout/git/ref/tags/v4")
+            .match_header("if-none-match", "\"abc123\"")
+            .with_status(304)
+            .create();
+
+        let cached = LockEntry {
+            key: LockKey {
+                kind: LockTargetKind::Action,
+                identifier: "actions/checkout".to_string(),
+                requested: "v4".to_string(),
+            },
+            resolved: "8ade135a41bc03ea155e62e844d188df1ea18608".to_string(),
+            resolved_tag: "v4".to_string(),
+            resolved_at: "2025-01-01T00:00:00Z".to_string(),
+            etag: Some("\"abc123\"".to_string()),
+        };
+
+        let client = reqwest::blocking::Client::new();
+        let result = get_commit_sha_for_tag(
+            &client,
+            &server.url(),
+            "actions",
+            "checkout",
+            "v4",
+            Some(&cached),
+        );
+
+        match result.unwrap() {
+            TagLookup::NotModified(sha, etag) => {
+                assert_eq!(sha, "8ade135a41bc03ea155e62e844d188df1ea18608");
+                assert_eq!(etag, Some("\"abc123\"".to_string()));
+            }
+            _ => panic!("expected a cache hit from the 304 response"),
+        }
+    }
+
+    #[test]
+    fn test_rate_limit_backoff_uses_reset_header() {
+        use mockito::Server;
+
+        let mut server = Server::new();
+        let reset_epoch = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs()
+            + 5;
+
+        let _mock = server
+            .mock("GET", "/ping")
+            .with_status(403)
+            .with_header("x-ratelimit-reset", &reset_epoch.to_string())
+            .create();
+
+        let client = reqwest::blocking::Client::new();
+        let response = client.get(format!("{}/ping", server.url())).send().unwrap();
+
+        let wait = rate_limit_backoff(&response, 0);
+        // 5s until reset, stretched by up to 20% jitter (at most 6s); the 6.5s bound leaves headroom.
+        assert!(wait.as_secs_f64() >= 5.0 && wait.as_secs_f64() <= 6.5);
+    }
+
+    #[test]
+    fn test_ref_cache_roundtrips_and_honors_ttl() {
+        use tempfile::TempDir;
+
+        let temp_dir = TempDir::new().unwrap();
+        let cache = RefCache::open(&temp_dir.path().join("cache.sqlite3")).unwrap();
+
+        let key = LockKey {
+            kind: LockTargetKind::Action,
+            identifier: "actions/checkout".to_string(),
+            requested: "v4".to_string(),
+        };
+
+        assert!(cache.get(&key).unwrap().is_none());
+
+        cache
+            .put(&key, "8ade135a41bc03ea155e62e844d188df1ea18608", "v4.2.0", Some("\"etag1\""))
+            .unwrap();
+
+        let row = cache.get(&key).unwrap().unwrap();
+        assert_eq!(row.resolved, "8ade135a41bc03ea155e62e844d188df1ea18608");
+        assert_eq!(row.resolved_tag, "v4.2.0");
+        assert_eq!(row.etag, Some("\"etag1\"".to_string()));
+        assert!(row.is_fresh(Duration::from_secs(3600)));
+
+        let stale = CachedResolution {
+            resolved: row.resolved,
+            resolved_tag: row.resolved_tag,
+            etag: row.etag,
+            fetched_at: row.fetched_at - 7200,
+        };
+        assert!(!stale.is_fresh(Duration::from_secs(3600)));
+    }
+
+    #[test]
+    fn test_find_deprecated_commands() {
+        let input = r#"
+jobs:
+  test:
+    steps:
+      - run: echo "::set-output name=foo::bar"
+      - run: echo "hello"
+      - run: echo "::save-state name=token::abc123"
+"#;
+
+        let found = find_deprecated_commands(input);
+        assert_eq!(found.len(), 2);
+        assert_eq!(found[0].0, 5);
+        assert_eq!(found[1].0, 7);
+    }
+
+    #[test]
+    fn test_fix_deprecated_commands_rewrites_set_output() {
+        let input = "      - run: echo \"::set-output name=foo::bar\"\n";
+        let (fixed, changed) = fix_deprecated_commands(input);
+
+        assert_eq!(changed, 1);
+        assert_eq!(
+            fixed,
+            "      - run: echo \"foo=bar\" >> \"$GITHUB_OUTPUT\"\n"
+        );
+    }
+
+    #[test]
+    fn test_resolve_latest_for_pinned_action_moves_pin_forward() {
+        use mockito::Server;
+
+        let mut server = Server::new();
+        let _mock_release = server
+            .mock("GET", "/repos/actions/checkout/releases/latest")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(r#"{"tag_name": "v4.2.0"}"#)
+            .create();
+
+        let _mock_tag = server
+            .mock("GET", "/repos/actions/checkout/git/ref/tags/v4.2.0")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(r#"{"object": {"sha": "22222222222222222222222222222222222222bb"}}"#)
+            .create();
+
+        let client = reqwest::blocking::Client::new();
+        let result =
+            resolve_latest_for_pinned_action(&client, &server.url(), "actions/checkout").unwrap();
+
+        assert_eq!(
+            result,
+            Some((
+                "22222222222222222222222222222222222222bb".to_string(),
+                "v4.2.0".to_string()
+            ))
+        );
+    }
+
+    #[test]
+    fn test_resolve_latest_for_pinned_action_no_release_returns_none() {
+        use mockito::Server;
+
+        let mut server = Server::new();
+        let _mock_release = server
+            .mock("GET", "/repos/actions/checkout/releases/latest")
+            .with_status(404)
+            .create();
+
+        let client = reqwest::blocking::Client::new();
+        let result =
+            resolve_latest_for_pinned_action(&client, &server.url(), "actions/checkout").unwrap();
+
+        assert_eq!(result, None);
+    }
+
+    #[test]
+    fn test_rewrite_line_value_distinguishes_duplicate_occurrences_by_cursor() {
+        let content = "jobs:\n  a:\n    steps:\n      - uses: actions/checkout@v4\n  b:\n    steps:\n      - uses: actions/checkout@v4\n";
+        let mut lines: Vec<String> = content.lines().map(|l| l.to_string()).collect();
+        let mut cursor = 0usize;
+
+        let replaced_first = rewrite_line_value(
+            &mut lines,
+            &mut cursor,
+            "uses:",
+            "actions/checkout@v4",
+            "actions/checkout@aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa # v4",
+        );
+        assert!(replaced_first);
+        assert!(lines[3].contains("aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa"));
+        assert!(lines[6].contains("actions/checkout@v4"));
+
+        let replaced_second = rewrite_line_value(
+            &mut lines,
+            &mut cursor,
+            "uses:",
+            "actions/checkout@v4",
+            "actions/checkout@bbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbb # v4",
+        );
+        assert!(replaced_second);
+        assert!(lines[6].contains("bbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbb"));
+    }
+
+    #[test]
+    fn test_apply_resolved_pins_moves_already_pinned_action_forward() {
+        use std::fs;
+        use tempfile::TempDir;
+
+        let workflow = "jobs:\n  test:\n    steps:\n      - uses: actions/checkout@aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa # v4\n";
+
+        let temp_dir = TempDir::new().unwrap();
+        let workflow_file = temp_dir.path().join("test.yaml");
+        fs::write(&workflow_file, workflow).unwrap();
+
+        let pin_targets = find_action_references(workflow).unwrap();
+        assert_eq!(pin_targets.len(), 1);
+
+        let lock_key = lock_key_for_target(&pin_targets[0]);
+        let mut resolved = HashMap::new();
+        resolved.insert(
+            lock_key,
+            Ok((
+                "bbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbb".to_string(),
+                "v4.2.0".to_string(),
+                None,
+            )),
+        );
+
+        let mut lockfile = LockFile::default();
+        apply_resolved_pins(&workflow_file, workflow, &pin_targets, &resolved, &mut lockfile, false)
+            .unwrap();
+
+        let updated = fs::read_to_string(&workflow_file).unwrap();
+        assert!(updated.contains("actions/checkout@bbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbb # v4.2.0"));
+        assert!(!updated.contains("aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa"));
+    }
+
+    #[test]
+    fn test_parse_www_authenticate_defaults_scope() {
+        let (realm, service, scope) = parse_www_authenticate(
+            r#"Bearer realm="https://auth.docker.io/token",service="registry.docker.io""#,
+            "library/alpine",
+        )
+        .unwrap();
+
+        assert_eq!(realm, "https://auth.docker.io/token");
+        assert_eq!(service, "registry.docker.io");
+        assert_eq!(scope, Some("repository:library/alpine:pull".to_string()));
+    }
+
+    #[test]
+    fn test_parse_www_authenticate_respects_explicit_scope() {
+        let (_, _, scope) = parse_www_authenticate(
+            r#"Bearer realm="https://auth.docker.io/token",service="registry.docker.io",scope="repository:org/img:pull""#,
+            "org/img",
+        )
+        .unwrap();
+
+        assert_eq!(scope, Some("repository:org/img:pull".to_string()));
+    }
+
+    #[test]
+    fn test_authenticate_with_registry_exchanges_challenge_for_token() {
+        use mockito::Server;
+
+        let mut server = Server::new();
+
+        let _mock_challenge = server
+            .mock("HEAD", "/v2/org/img/manifests/v1.2.3")
+            .with_status(401)
+            .with_header(
+                "WWW-Authenticate",
+                &format!(r#"Bearer realm="{}/token",service="registry.example.com""#, server.url()),
+            )
+            .create();
+
+        let _mock_token = server
+            .mock("GET", "/token")
+            .match_query(mockito::Matcher::Any)
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(r#"{"token": "mocktoken"}"#)
+            .create();
+
+        let client = reqwest::blocking::Client::new();
+        let manifest_url = format!("{}/v2/org/img/manifests/v1.2.3", server.url());
+        let challenge = client.head(&manifest_url).send().unwrap();
+
+        let token = authenticate_with_registry(&client, &challenge, "org/img").unwrap();
+        assert_eq!(token, "mocktoken");
+    }
+
+    #[test]
+    fn test_resolve_image_digest_without_auth() {
+        use mockito::Server;
+
+        let mut server = Server::new();
+
+        let _mock = server
+            .mock("HEAD", "/v2/library/alpine/manifests/3.19")
+            .with_status(200)
+            .with_header("Docker-Content-Digest", "sha256:abcdef0123456789")
+            .create();
+
+        let client = reqwest::blocking::Client::new();
+        let digest =
+            resolve_image_digest_with_base(&client, &server.url(), "library/alpine", "3.19").unwrap();
+
+        assert_eq!(digest, "sha256:abcdef0123456789");
+    }
+
+    #[test]
+    fn test_resolve_image_digest_retries_with_bearer_token_after_401() {
+        use mockito::Server;
+
+        let mut server = Server::new();
+
+        let _mock_challenge = server
+            .mock("HEAD", "/v2/org/img/manifests/v1.2.3")
+            .match_header("authorization", mockito::Matcher::Missing)
+            .with_status(401)
+            .with_header(
+                "WWW-Authenticate",
+                &format!(r#"Bearer realm="{}/token",service="registry.example.com""#, server.url()),
+            )
+            .create();
+
+        let _mock_token = server
+            .mock("GET", "/token")
+            .match_query(mockito::Matcher::Any)
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(r#"{"token": "mocktoken"}"#)
+            .create();
+
+        let _mock_authed = server
+            .mock("HEAD", "/v2/org/img/manifests/v1.2.3")
+            .match_header("authorization", "Bearer mocktoken")
+            .with_status(200)
+            .with_header("Docker-Content-Digest", "sha256:deadbeef")
+            .create();
+
+        let client = reqwest::blocking::Client::new();
+        let digest =
+            resolve_image_digest_with_base(&client, &server.url(), "org/img", "v1.2.3").unwrap();
+
+        assert_eq!(digest, "sha256:deadbeef");
+    }
+
+    #[test]
+    fn test_resolve_image_digest_get_fallback_keeps_bearer_token() {
+        use mockito::Server;
+
+        let mut server = Server::new();
+
+        let _mock_challenge = server
+            .mock("HEAD", "/v2/org/img/manifests/v1.2.3")
+            .match_header("authorization", mockito::Matcher::Missing)
+            .with_status(401)
+            .with_header(
+                "WWW-Authenticate",
+                &format!(r#"Bearer realm="{}/token",service="registry.example.com""#, server.url()),
+            )
+            .create();
+
+        let _mock_token = server
+            .mock("GET", "/token")
+            .match_query(mockito::Matcher::Any)
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(r#"{"token": "mocktoken"}"#)
+            .create();
+
+        // This registry responds poorly to HEAD even once authenticated...
+        let _mock_head_authed_fails = server
+            .mock("HEAD", "/v2/org/img/manifests/v1.2.3")
+            .match_header("authorization", "Bearer mocktoken")
+            .with_status(500)
+            .create();
+
+        // ...so the GET fallback must carry the same Bearer token, not drop it.
+        let _mock_get_authed = server
+            .mock("GET", "/v2/org/img/manifests/v1.2.3")
+            .match_header("authorization", "Bearer mocktoken")
+            .with_status(200)
+            .with_header("Docker-Content-Digest", "sha256:c0ffee")
+            .create();
+
+        let client = reqwest::blocking::Client::new();
+        let digest =
+            resolve_image_digest_with_base(&client, &server.url(), "org/img", "v1.2.3").unwrap();
+
+        assert_eq!(digest, "sha256:c0ffee");
     }
 }